@@ -0,0 +1,160 @@
+use anyhow::{anyhow, Result};
+
+use crate::hex::{self, FirmwareImage};
+
+const PT_LOAD: u32 = 1;
+
+/// parse a little-endian 32-bit ELF and collect its loadable segments into
+/// a flat `FirmwareImage`, the same shape `hex::parse` produces for Intel HEX
+pub fn parse(data: &[u8]) -> Result<FirmwareImage> {
+    let header = ElfHeader::read(data)?;
+
+    let mut segments: Vec<(u32, Vec<u8>)> = Vec::new();
+
+    for i in 0..header.phnum {
+        let off = header.phoff as usize + i as usize * header.phentsize as usize;
+        let phdr = ProgramHeader::read(data, off)?;
+
+        if phdr.p_type != PT_LOAD || phdr.filesz == 0 {
+            continue;
+        }
+
+        if phdr.paddr < hex::FLASH_BASE {
+            return Err(anyhow!(
+                "segment at 0x{:08X} is below the Teensy flash window (0x{:08X})",
+                phdr.paddr,
+                hex::FLASH_BASE
+            ));
+        }
+
+        let start = phdr.offset as usize;
+        let end = start
+            .checked_add(phdr.filesz as usize)
+            .ok_or_else(|| anyhow!("segment at 0x{:08X} overflows the file", phdr.paddr))?;
+        let bytes = data
+            .get(start..end)
+            .ok_or_else(|| anyhow!("segment at 0x{:08X} extends past end of file", phdr.paddr))?;
+
+        segments.push((phdr.paddr, bytes.to_vec()));
+    }
+
+    hex::build_image(segments)
+}
+
+struct ElfHeader {
+    phoff: u64,
+    phentsize: u16,
+    phnum: u16,
+}
+
+impl ElfHeader {
+    fn read(data: &[u8]) -> Result<Self> {
+        if data.len() < 20 {
+            return Err(anyhow!("ELF file is too short"));
+        }
+
+        let ei_class = data[4];
+        let ei_data = data[5];
+
+        if ei_data != 1 {
+            return Err(anyhow!("only little-endian ELF files are supported"));
+        }
+
+        match ei_class {
+            1 => {
+                // ELFCLASS32
+                if data.len() < 52 {
+                    return Err(anyhow!("ELF32 header is truncated"));
+                }
+                Ok(ElfHeader {
+                    phoff: u32::from_le_bytes(data[28..32].try_into().unwrap()) as u64,
+                    phentsize: u16::from_le_bytes(data[42..44].try_into().unwrap()),
+                    phnum: u16::from_le_bytes(data[44..46].try_into().unwrap()),
+                })
+            }
+            2 => Err(anyhow!(
+                "64-bit ELF files are not supported (Teensy 4.x is a 32-bit target)"
+            )),
+            _ => Err(anyhow!("not a valid ELF file")),
+        }
+    }
+}
+
+struct ProgramHeader {
+    p_type: u32,
+    offset: u32,
+    paddr: u32,
+    filesz: u32,
+}
+
+impl ProgramHeader {
+    fn read(data: &[u8], off: usize) -> Result<Self> {
+        let phdr = data
+            .get(off..off + 32)
+            .ok_or_else(|| anyhow!("program header at offset 0x{:X} is out of bounds", off))?;
+
+        Ok(ProgramHeader {
+            p_type: u32::from_le_bytes(phdr[0..4].try_into().unwrap()),
+            offset: u32::from_le_bytes(phdr[4..8].try_into().unwrap()),
+            paddr: u32::from_le_bytes(phdr[12..16].try_into().unwrap()),
+            filesz: u32::from_le_bytes(phdr[16..20].try_into().unwrap()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // builds a minimal ELF32 LE file with one PT_LOAD segment of `data`
+    // placed at `paddr`, plus one PT_NULL segment that should be ignored
+    fn build_elf32(paddr: u32, data: &[u8]) -> Vec<u8> {
+        const EHSIZE: usize = 52;
+        const PHENTSIZE: usize = 32;
+
+        let mut file = vec![0u8; EHSIZE];
+        file[0..4].copy_from_slice(&[0x7F, b'E', b'L', b'F']);
+        file[4] = 1; // ELFCLASS32
+        file[5] = 1; // little-endian
+        file[28..32].copy_from_slice(&(EHSIZE as u32).to_le_bytes()); // e_phoff
+        file[42..44].copy_from_slice(&(PHENTSIZE as u16).to_le_bytes()); // e_phentsize
+        file[44..46].copy_from_slice(&2u16.to_le_bytes()); // e_phnum
+
+        let data_offset = EHSIZE + 2 * PHENTSIZE;
+
+        // PT_NULL segment, should be skipped
+        let mut null_phdr = vec![0u8; PHENTSIZE];
+        null_phdr[0..4].copy_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&null_phdr);
+
+        // PT_LOAD segment
+        let mut load_phdr = vec![0u8; PHENTSIZE];
+        load_phdr[0..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+        load_phdr[4..8].copy_from_slice(&(data_offset as u32).to_le_bytes());
+        load_phdr[12..16].copy_from_slice(&paddr.to_le_bytes());
+        load_phdr[16..20].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        file.extend_from_slice(&load_phdr);
+
+        file.extend_from_slice(data);
+        file
+    }
+
+    #[test]
+    fn test_parse_single_segment() {
+        let file = build_elf32(hex::FLASH_BASE, &[0xAA, 0xBB, 0xCC, 0xDD]);
+        let image = parse(&file).unwrap();
+        assert_eq!(image.base_address, hex::FLASH_BASE);
+        assert_eq!(image.data[0..4], [0xAA, 0xBB, 0xCC, 0xDD]);
+    }
+
+    #[test]
+    fn test_segment_below_flash_base_errors() {
+        let file = build_elf32(hex::FLASH_BASE - 0x1000, &[0x01]);
+        assert!(parse(&file).is_err());
+    }
+
+    #[test]
+    fn test_not_an_elf_file() {
+        assert!(ElfHeader::read(b"not an elf file at all").is_err());
+    }
+}
@@ -4,6 +4,21 @@ use std::time::{Duration, Instant};
 use crate::hex::FirmwareImage;
 use crate::usb::{self, TeensyDevice};
 
+// output report that tells a running sketch's seremu interface to jump
+// into the HalfKay bootloader: HID report ID 0 followed by the 0xA9
+// reboot command byte (unlike `reboot` below, which targets HalfKay itself)
+const SEREMU_REBOOT_REPORT: [u8; 2] = [0, 0xA9];
+
+/// ask a running Teensy sketch to reboot into the HalfKay bootloader via
+/// its application-mode "debug/seremu" interface
+pub fn soft_reboot(vid: u16, seremu_pid: u16) -> Result<()> {
+    let device = usb::open_seremu(vid, seremu_pid).context("no running Teensy found")?;
+    device
+        .write(&SEREMU_REBOOT_REPORT)
+        .context("failed to send reboot command")?;
+    Ok(())
+}
+
 // timing from PJRC teensy_loader_cli
 // first blocks need long timeout because block 0 triggers full chip erase
 const ERASE_BLOCK_COUNT: usize = 4;
@@ -21,20 +36,19 @@ pub fn flash_with_progress(
     image: &FirmwareImage,
     on_block: impl Fn(usize),
 ) -> Result<()> {
-    let total_blocks = image.data.len() / usb::BLOCK_SIZE;
+    let total_blocks = image.block_count();
     let mut report = [0u8; usb::REPORT_SIZE];
 
     for i in 0..total_blocks {
-        let offset = i * usb::BLOCK_SIZE;
-        let block = &image.data[offset..offset + usb::BLOCK_SIZE];
-
         // first block must always be sent (triggers chip erase)
         // skip subsequent blocks that are blank (all 0xFF)
-        if i > 0 && block.iter().all(|&b| b == 0xFF) {
+        if i > 0 && image.is_blank_block(i) {
             continue;
         }
 
         on_block(i);
+        let offset = i * usb::BLOCK_SIZE;
+        let block = &image.data[offset..offset + usb::BLOCK_SIZE];
         fill_block_report(&mut report, offset, block);
         write_with_retry(device, &report, i).with_context(|| {
             format!(
@@ -48,6 +62,12 @@ pub fn flash_with_progress(
     Ok(())
 }
 
+/// whether `block_index` falls in the long-timeout erase phase (the first
+/// few blocks trigger and ride out the chip erase before writing starts)
+pub fn is_erase_phase(block_index: usize) -> bool {
+    block_index <= ERASE_BLOCK_COUNT
+}
+
 pub fn reboot(device: &mut TeensyDevice) -> Result<()> {
     let mut report = [0u8; usb::REPORT_SIZE];
     fill_boot_report(&mut report);
@@ -2,17 +2,33 @@ use anyhow::{anyhow, Result};
 use std::fs;
 use std::path::Path;
 
+use crate::elf;
 use crate::usb;
 
+/// start of the Teensy 4.x internal flash window; images with data below
+/// this address aren't addressable on the chip
+pub const FLASH_BASE: u32 = 0x6000_0000;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+
 /// flat memory image ready to flash, aligned to block boundaries
 pub struct FirmwareImage {
     pub data: Vec<u8>,
     pub base_address: u32,
+    /// length of `data` before block-alignment padding was added
+    pub raw_len: usize,
 }
 
 impl FirmwareImage {
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let content = fs::read_to_string(path)?;
+        let bytes = fs::read(path)?;
+
+        if bytes.starts_with(&ELF_MAGIC) {
+            return elf::parse(&bytes);
+        }
+
+        let content =
+            String::from_utf8(bytes).map_err(|_| anyhow!("file is not a valid Intel HEX file"))?;
         Self::parse(&content)
     }
 
@@ -54,29 +70,7 @@ impl FirmwareImage {
             }
         }
 
-        if segments.is_empty() {
-            return Err(anyhow!("hex file contains no data"));
-        }
-
-        // determine address range
-        let base_address = segments.iter().map(|(a, _)| *a).min().unwrap();
-        let end_address = segments
-            .iter()
-            .map(|(a, d)| *a + d.len() as u32)
-            .max()
-            .unwrap();
-
-        // build flat image, aligned to block size
-        let raw_size = (end_address - base_address) as usize;
-        let aligned_size = raw_size.div_ceil(usb::BLOCK_SIZE) * usb::BLOCK_SIZE;
-        let mut data = vec![0xFFu8; aligned_size];
-
-        for (addr, segment_data) in &segments {
-            let offset = (*addr - base_address) as usize;
-            data[offset..offset + segment_data.len()].copy_from_slice(segment_data);
-        }
-
-        Ok(FirmwareImage { data, base_address })
+        build_image(segments)
     }
 
     pub fn block_count(&self) -> usize {
@@ -86,6 +80,129 @@ impl FirmwareImage {
     pub fn byte_count(&self) -> usize {
         self.data.len()
     }
+
+    /// number of blocks `halfkay::flash` will actually transmit: block 0 is
+    /// always sent (it triggers the chip erase) and blank (`0xFF`) blocks
+    /// after it are skipped
+    pub fn written_block_count(&self) -> usize {
+        (0..self.block_count())
+            .filter(|&i| i == 0 || !self.is_blank_block(i))
+            .count()
+    }
+
+    pub(crate) fn is_blank_block(&self, index: usize) -> bool {
+        let offset = index * usb::BLOCK_SIZE;
+        self.data[offset..offset + usb::BLOCK_SIZE]
+            .iter()
+            .all(|&b| b == 0xFF)
+    }
+
+    /// the image bytes, optionally trimmed of trailing block-alignment
+    /// padding that was added to round up to `usb::BLOCK_SIZE`
+    pub fn bytes(&self, pad: bool) -> &[u8] {
+        if pad {
+            &self.data
+        } else {
+            &self.data[..self.raw_len]
+        }
+    }
+
+    /// CRC-32 (IEEE 802.3) of the image bytes, for comparing against build
+    /// artifacts or verifying what was flashed
+    pub fn crc32(&self, pad: bool) -> u32 {
+        crc32(self.bytes(pad))
+    }
+}
+
+/// lay segments out into a flat, block-aligned image
+///
+/// shared by the Intel HEX and ELF loaders so both produce byte-for-byte
+/// identical `FirmwareImage`s for the same memory contents
+pub(crate) fn build_image(segments: Vec<(u32, Vec<u8>)>) -> Result<FirmwareImage> {
+    if segments.is_empty() {
+        return Err(anyhow!("firmware file contains no data"));
+    }
+
+    // determine address range
+    let base_address = segments.iter().map(|(a, _)| *a).min().unwrap();
+    let end_address = segments
+        .iter()
+        .map(|(a, d)| *a + d.len() as u32)
+        .max()
+        .unwrap();
+
+    // build flat image, aligned to block size
+    let raw_size = (end_address - base_address) as usize;
+    let aligned_size = raw_size.div_ceil(usb::BLOCK_SIZE) * usb::BLOCK_SIZE;
+    let mut data = vec![0xFFu8; aligned_size];
+
+    for (addr, segment_data) in &segments {
+        let offset = (*addr - base_address) as usize;
+        data[offset..offset + segment_data.len()].copy_from_slice(segment_data);
+    }
+
+    Ok(FirmwareImage {
+        data,
+        base_address,
+        raw_len: raw_size,
+    })
+}
+
+/// re-emit a flat image as canonical Intel HEX: a type-04 extended linear
+/// address record up front and again whenever the upper 16 address bits
+/// change, 16-byte type-00 data records, and a type-01 EOF record, each
+/// with a correct checksum
+pub fn to_intel_hex(base_address: u32, data: &[u8]) -> String {
+    const RECORD_LEN: usize = 16;
+
+    let mut out = String::new();
+    let mut extended_address = None;
+
+    for (i, chunk) in data.chunks(RECORD_LEN).enumerate() {
+        let addr = base_address + (i * RECORD_LEN) as u32;
+        let high = (addr >> 16) as u16;
+
+        if extended_address != Some(high) {
+            write_record(&mut out, 0x04, 0, &high.to_be_bytes());
+            extended_address = Some(high);
+        }
+
+        write_record(&mut out, 0x00, (addr & 0xFFFF) as u16, chunk);
+    }
+
+    write_record(&mut out, 0x01, 0, &[]);
+    out
+}
+
+fn write_record(out: &mut String, record_type: u8, address: u16, data: &[u8]) {
+    let mut sum = data.len() as u8;
+    sum = sum.wrapping_add((address >> 8) as u8);
+    sum = sum.wrapping_add((address & 0xFF) as u8);
+    sum = sum.wrapping_add(record_type);
+    for &b in data {
+        sum = sum.wrapping_add(b);
+    }
+    let checksum = sum.wrapping_neg();
+
+    out.push(':');
+    out.push_str(&format!("{:02X}{:04X}{:02X}", data.len(), address, record_type));
+    for &b in data {
+        out.push_str(&format!("{:02X}", b));
+    }
+    out.push_str(&format!("{:02X}\n", checksum));
+}
+
+/// CRC-32 (IEEE 802.3), the same variant used by `zlib`/`gzip`
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
 }
 
 struct RawRecord {
@@ -183,4 +300,21 @@ mod tests {
         let image = FirmwareImage::parse(hex).unwrap();
         assert_eq!(image.data.len() % usb::BLOCK_SIZE, 0);
     }
+
+    #[test]
+    fn test_crc32_known_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_intel_hex_round_trip() {
+        let hex = ":0200000460009A\n:100000004643464200000156000000000103030081\n:00000001FF\n";
+        let image = FirmwareImage::parse(hex).unwrap();
+
+        let reemitted = to_intel_hex(image.base_address, image.bytes(false));
+        let reparsed = FirmwareImage::parse(&reemitted).unwrap();
+
+        assert_eq!(reparsed.base_address, image.base_address);
+        assert_eq!(reparsed.bytes(false), image.bytes(false));
+    }
 }
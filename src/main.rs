@@ -1,9 +1,12 @@
+mod elf;
 mod halfkay;
 mod hex;
+mod serial;
 mod usb;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use clap::{Parser, Subcommand};
+use std::io::Write;
 use std::path::PathBuf;
 use std::process;
 use std::time::Duration;
@@ -22,9 +25,9 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Command {
-    /// flash a hex file onto the connected Teensy
+    /// flash a firmware file onto the connected Teensy
     Flash {
-        /// path to the Intel HEX file
+        /// firmware file (Intel HEX or ELF)
         hex_file: PathBuf,
 
         /// wait for device to appear in bootloader mode
@@ -34,10 +37,108 @@ enum Command {
         /// how long to wait for device in seconds (default: 30)
         #[arg(short, long, default_value = "30")]
         timeout: u64,
+
+        /// bootloader USB VID:PID to look for, in hex (default: the Teensy VID:PID)
+        #[arg(long, value_parser = parse_vid_pid)]
+        device: Option<(u16, u16)>,
+
+        /// don't try to reboot a running Teensy into the bootloader;
+        /// require the button to have been pressed already
+        #[arg(long)]
+        no_auto_reboot: bool,
+
+        /// open a serial monitor once flashing and reboot are done
+        #[arg(long)]
+        monitor: bool,
+
+        /// baud rate for --monitor (ignored by native USB serial, but some
+        /// sketches key off it)
+        #[arg(long, default_value = "9600")]
+        baud: u32,
+
+        /// serial number of the target board, required if several are attached
+        #[arg(long)]
+        serial: Option<String>,
+
+        /// suppress the progress bar, for scripting
+        #[arg(short, long)]
+        quiet: bool,
     },
 
     /// list Teensy devices in bootloader mode
-    List,
+    List {
+        /// bootloader USB VID:PID to look for, in hex (default: the Teensy VID:PID)
+        #[arg(long, value_parser = parse_vid_pid)]
+        device: Option<(u16, u16)>,
+    },
+
+    /// open a serial monitor to a Teensy's USB CDC-ACM port
+    Monitor {
+        /// USB vendor ID to look for, in hex (default: 16c0)
+        #[arg(long, value_parser = parse_hex_u16, default_value = "16c0")]
+        vid: u16,
+
+        /// baud rate (ignored by native USB serial, but some sketches key off it)
+        #[arg(short, long, default_value = "9600")]
+        baud: u32,
+
+        /// how long to wait for the serial port to appear, in seconds
+        #[arg(short, long, default_value = "10")]
+        timeout: u64,
+    },
+
+    /// convert a firmware image (HEX or ELF) to a flat binary or Intel HEX
+    Convert {
+        /// input firmware file (Intel HEX or ELF)
+        input: PathBuf,
+
+        /// output file; format is chosen from the extension unless --format is given
+        output: PathBuf,
+
+        /// output format, overriding the extension guess
+        #[arg(long, value_enum)]
+        format: Option<OutputFormat>,
+
+        /// strip block-aligned 0xFF padding from the output (kept by default)
+        #[arg(long)]
+        no_pad: bool,
+    },
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum OutputFormat {
+    Bin,
+    Ihex,
+}
+
+impl OutputFormat {
+    fn from_extension(path: &std::path::Path) -> Result<Self> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("bin") => Ok(OutputFormat::Bin),
+            Some("hex") => Ok(OutputFormat::Ihex),
+            _ => Err(anyhow!(
+                "can't guess format from {}; pass --format bin|ihex",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// parse a `vid:pid` pair in hex, e.g. `16c0:0478`
+fn parse_vid_pid(s: &str) -> Result<(u16, u16), String> {
+    let (vid, pid) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected VID:PID in hex, e.g. 16c0:0478 (got {:?})", s))?;
+
+    let vid = u16::from_str_radix(vid, 16).map_err(|_| format!("invalid VID: {:?}", vid))?;
+    let pid = u16::from_str_radix(pid, 16).map_err(|_| format!("invalid PID: {:?}", pid))?;
+
+    Ok((vid, pid))
+}
+
+/// parse a bare hex value, e.g. `16c0`
+fn parse_hex_u16(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(s, 16).map_err(|_| format!("invalid hex value: {:?}", s))
 }
 
 fn main() {
@@ -48,22 +149,81 @@ fn main() {
             hex_file,
             wait,
             timeout,
-        } => run_flash(hex_file, wait, timeout),
-        Command::List => run_list(),
+            device,
+            no_auto_reboot,
+            monitor,
+            baud,
+            serial,
+            quiet,
+        } => run_flash(
+            hex_file,
+            wait,
+            timeout,
+            device.unwrap_or((usb::TEENSY_VID, usb::TEENSY_4X_PID)),
+            no_auto_reboot,
+            monitor,
+            baud,
+            serial,
+            quiet,
+        ),
+        Command::List { device } => {
+            run_list(device.unwrap_or((usb::TEENSY_VID, usb::TEENSY_4X_PID)))
+        }
+        Command::Monitor { vid, baud, timeout } => run_monitor(vid, baud, timeout),
+        Command::Convert {
+            input,
+            output,
+            format,
+            no_pad,
+        } => run_convert(input, output, format, no_pad),
     };
 
     process::exit(code);
 }
 
-fn run_flash(hex_file: PathBuf, wait: bool, timeout_secs: u64) -> i32 {
-    if let Err(e) = flash(hex_file, wait, timeout_secs) {
+#[allow(clippy::too_many_arguments)]
+fn run_flash(
+    hex_file: PathBuf,
+    wait: bool,
+    timeout_secs: u64,
+    device: (u16, u16),
+    no_auto_reboot: bool,
+    monitor: bool,
+    baud: u32,
+    serial: Option<String>,
+    quiet: bool,
+) -> i32 {
+    if let Err(e) = flash(
+        hex_file,
+        wait,
+        timeout_secs,
+        device,
+        no_auto_reboot,
+        monitor,
+        baud,
+        serial,
+        quiet,
+    ) {
         eprintln!("error: {:#}", e);
         return 1;
     }
     0
 }
 
-fn flash(hex_file: PathBuf, wait: bool, timeout_secs: u64) -> Result<()> {
+#[allow(clippy::too_many_arguments)]
+fn flash(
+    hex_file: PathBuf,
+    wait: bool,
+    timeout_secs: u64,
+    (vid, pid): (u16, u16),
+    no_auto_reboot: bool,
+    monitor: bool,
+    baud: u32,
+    serial: Option<String>,
+    quiet: bool,
+) -> Result<()> {
+    let serial = serial.as_deref();
+
     let image = FirmwareImage::from_file(&hex_file)
         .with_context(|| format!("failed to read {}", hex_file.display()))?;
 
@@ -74,24 +234,174 @@ fn flash(hex_file: PathBuf, wait: bool, timeout_secs: u64) -> Result<()> {
         image.block_count(),
     );
 
-    let mut device = if wait {
-        let timeout = Duration::from_secs(timeout_secs);
-        eprintln!("waiting for device ({}s timeout)...", timeout_secs);
-        TeensyDevice::open_wait(timeout).context("device not found")?
-    } else {
-        TeensyDevice::open().context("device not found")?
-    };
+    let timeout = Duration::from_secs(timeout_secs);
+    let mut device = open_or_reboot(vid, pid, serial, no_auto_reboot, wait, timeout)?;
+
+    if let Some(board) = usb::board_for_pid(device.pid()) {
+        if image.byte_count() > board.flash_size {
+            return Err(anyhow!(
+                "image is {} but {} has {} usable",
+                format_mb(image.byte_count()),
+                board.name,
+                format_mb(board.flash_size),
+            ));
+        }
+        eprintln!("target: {}", board.name);
+    }
 
     eprintln!("flashing...");
-    halfkay::flash(&mut device, &image).context("flash failed")?;
+    if quiet {
+        halfkay::flash(&mut device, &image).context("flash failed")?;
+    } else {
+        let total = image.written_block_count();
+        let written = std::cell::Cell::new(0usize);
+        halfkay::flash_with_progress(&mut device, &image, |i| {
+            written.set(written.get() + 1);
+            let phase = if halfkay::is_erase_phase(i) {
+                "erasing chip..."
+            } else {
+                "writing"
+            };
+            print_progress(phase, written.get(), total);
+        })
+        .context("flash failed")?;
+        eprintln!();
+    }
     halfkay::reboot(&mut device).context("reboot failed")?;
     eprintln!("done");
 
+    if monitor {
+        let timeout = Duration::from_secs(timeout_secs);
+        eprintln!("waiting for serial port...");
+        let port_name = serial::find_port(vid, timeout)?;
+        serial::monitor(&port_name, baud)?;
+    }
+
+    Ok(())
+}
+
+/// open a device already in bootloader mode, or, failing that, soft-reboot
+/// a running Teensy into the bootloader and wait for it to reappear
+///
+/// `wait` and `no_auto_reboot` are independent: `wait` controls whether we
+/// keep polling for a button-pressed device when no reboot is attempted (or
+/// possible), while `no_auto_reboot` only disables the soft-reboot attempt
+/// itself
+fn open_or_reboot(
+    vid: u16,
+    pid: u16,
+    serial: Option<&str>,
+    no_auto_reboot: bool,
+    wait: bool,
+    timeout: Duration,
+) -> Result<TeensyDevice> {
+    match TeensyDevice::open(vid, pid, serial) {
+        Ok(device) => Ok(device),
+        Err(e) if no_auto_reboot && !wait => Err(e).context("device not found"),
+        Err(_) if no_auto_reboot => {
+            eprintln!("waiting for device ({:?} timeout)...", timeout);
+            TeensyDevice::open_wait(vid, pid, serial, timeout).context("device not found")
+        }
+        Err(_) => {
+            eprintln!("no bootloader device found, rebooting a running Teensy...");
+            match halfkay::soft_reboot(vid, usb::TEENSY_SEREMU_PID) {
+                Ok(()) => TeensyDevice::open_wait(vid, pid, serial, timeout)
+                    .context("device not found after reboot"),
+                Err(e) if !wait => Err(e).context("failed to trigger a soft reboot"),
+                Err(_) => {
+                    eprintln!("waiting for device ({:?} timeout)...", timeout);
+                    TeensyDevice::open_wait(vid, pid, serial, timeout).context("device not found")
+                }
+            }
+        }
+    }
+}
+
+fn format_mb(bytes: usize) -> String {
+    format!("{:.2} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn print_progress(phase: &str, done: usize, total: usize) {
+    const WIDTH: usize = 30;
+    let filled = if total == 0 { WIDTH } else { done * WIDTH / total };
+    eprint!(
+        "\r{:<16} [{}{}] {}/{}",
+        phase,
+        "=".repeat(filled),
+        " ".repeat(WIDTH - filled),
+        done,
+        total,
+    );
+    let _ = std::io::stderr().flush();
+}
+
+fn run_monitor(vid: u16, baud: u32, timeout_secs: u64) -> i32 {
+    if let Err(e) = monitor(vid, baud, timeout_secs) {
+        eprintln!("error: {:#}", e);
+        return 1;
+    }
+    0
+}
+
+fn monitor(vid: u16, baud: u32, timeout_secs: u64) -> Result<()> {
+    eprintln!("waiting for serial port ({}s timeout)...", timeout_secs);
+    let port_name = serial::find_port(vid, Duration::from_secs(timeout_secs))?;
+    serial::monitor(&port_name, baud)
+}
+
+fn run_convert(
+    input: PathBuf,
+    output: PathBuf,
+    format: Option<OutputFormat>,
+    no_pad: bool,
+) -> i32 {
+    if let Err(e) = convert(input, output, format, no_pad) {
+        eprintln!("error: {:#}", e);
+        return 1;
+    }
+    0
+}
+
+fn convert(
+    input: PathBuf,
+    output: PathBuf,
+    format: Option<OutputFormat>,
+    no_pad: bool,
+) -> Result<()> {
+    let image = FirmwareImage::from_file(&input)
+        .with_context(|| format!("failed to read {}", input.display()))?;
+
+    let format = match format {
+        Some(f) => f,
+        None => OutputFormat::from_extension(&output)?,
+    };
+
+    let pad = !no_pad;
+    let bytes = image.bytes(pad);
+
+    match format {
+        OutputFormat::Bin => std::fs::write(&output, bytes)
+            .with_context(|| format!("failed to write {}", output.display()))?,
+        OutputFormat::Ihex => {
+            let text = hex::to_intel_hex(image.base_address, bytes);
+            std::fs::write(&output, text)
+                .with_context(|| format!("failed to write {}", output.display()))?;
+        }
+    }
+
+    eprintln!(
+        "{} -> {}: {} bytes, crc32 0x{:08X}",
+        input.display(),
+        output.display(),
+        bytes.len(),
+        image.crc32(pad),
+    );
+
     Ok(())
 }
 
-fn run_list() -> i32 {
-    match usb::list_devices() {
+fn run_list((vid, pid): (u16, u16)) -> i32 {
+    match usb::list_devices(vid, pid) {
         Ok(serials) if serials.is_empty() => {
             println!("no devices found");
             0
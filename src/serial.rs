@@ -0,0 +1,75 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// find the first USB CDC-ACM serial port matching `vid`, polling until it
+/// appears (a Teensy takes a moment to re-enumerate after reboot)
+pub fn find_port(vid: u16, timeout: Duration) -> Result<String> {
+    let start = Instant::now();
+
+    loop {
+        if let Ok(ports) = serialport::available_ports() {
+            for port in ports {
+                if let serialport::SerialPortType::UsbPort(info) = port.port_type {
+                    if info.vid == vid {
+                        return Ok(port.port_name);
+                    }
+                }
+            }
+        }
+
+        if start.elapsed() >= timeout {
+            return Err(anyhow!(
+                "no serial port found for vendor ID {:04x} within {:?}",
+                vid,
+                timeout
+            ));
+        }
+
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// stream a serial port's output to stdout and echo stdin to the device,
+/// until stdin reaches end of file, a write to the port fails, or the
+/// process is interrupted (Ctrl-C); if the port closes, output stops but
+/// this keeps waiting on stdin
+pub fn monitor(port_name: &str, baud: u32) -> Result<()> {
+    let mut port = serialport::new(port_name, baud)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .with_context(|| format!("failed to open {}", port_name))?;
+
+    let mut reader = port
+        .try_clone()
+        .context("failed to clone serial port handle")?;
+
+    eprintln!("connected to {} at {} baud (Ctrl-C to exit)", port_name, baud);
+
+    thread::spawn(move || {
+        let mut buf = [0u8; 256];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => continue,
+                Ok(n) => {
+                    let _ = io::stdout().write_all(&buf[..n]);
+                    let _ = io::stdout().flush();
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(_) => break,
+            }
+        }
+    });
+
+    let mut buf = [0u8; 256];
+    loop {
+        let n = io::stdin().read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        port.write_all(&buf[..n])?;
+    }
+
+    Ok(())
+}
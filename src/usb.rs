@@ -3,39 +3,115 @@ use hidapi::{HidApi, HidDevice};
 use std::ffi::CString;
 use std::time::{Duration, Instant};
 
-const TEENSY_VID: u16 = 0x16C0;
-const TEENSY_4X_PID: u16 = 0x0478;
+pub const TEENSY_VID: u16 = 0x16C0;
+pub const TEENSY_4X_PID: u16 = 0x0478;
+
+/// PID of the "debug/seremu" HID interface a running Teensy sketch exposes,
+/// used to ask the MCU to jump into the HalfKay bootloader without pressing
+/// the physical button
+pub const TEENSY_SEREMU_PID: u16 = 0x0486;
 
 pub const BLOCK_SIZE: usize = 1024;
 pub const HEADER_SIZE: usize = 64;
 pub const REPORT_SIZE: usize = 1 + HEADER_SIZE + BLOCK_SIZE; // report ID + header + data
 
+/// a known Teensy 4.x board variant, identified by its HalfKay bootloader PID
+pub struct Board {
+    pub name: &'static str,
+    pub pid: u16,
+    /// usable flash size in bytes, after the bootloader's own reservation
+    pub flash_size: usize,
+}
+
+pub const BOARDS: &[Board] = &[
+    Board {
+        name: "Teensy 4.0",
+        pid: 0x0478,
+        flash_size: 1_982 * 1024,
+    },
+    Board {
+        name: "Teensy 4.1",
+        pid: 0x0479,
+        flash_size: 7_936 * 1024,
+    },
+];
+
+/// look up the board model for a bootloader PID, if it's one we recognize
+pub fn board_for_pid(pid: u16) -> Option<&'static Board> {
+    BOARDS.iter().find(|b| b.pid == pid)
+}
+
 pub struct TeensyDevice {
     device: HidDevice,
     path: String,
+    vid: u16,
+    pid: u16,
+    /// serial number this device was selected by, if the caller asked for
+    /// one; `reopen` re-matches against this instead of grabbing whichever
+    /// device answers first
+    serial: Option<String>,
 }
 
 impl TeensyDevice {
-    pub fn open() -> Result<Self> {
+    pub fn open(vid: u16, pid: u16, serial: Option<&str>) -> Result<Self> {
         let api = HidApi::new()?;
 
-        for info in api.device_list() {
-            if info.vendor_id() != TEENSY_VID || info.product_id() != TEENSY_4X_PID {
-                continue;
-            }
+        let mut matches: Vec<_> = api
+            .device_list()
+            .filter(|info| info.vendor_id() == vid && info.product_id() == pid)
+            .filter(|info| match serial {
+                Some(want) => info.serial_number() == Some(want),
+                None => true,
+            })
+            .collect();
+
+        if matches.is_empty() {
+            return Err(match serial {
+                Some(sn) => anyhow!(
+                    "no device found for {:04x}:{:04x} with serial {} in bootloader mode",
+                    vid,
+                    pid,
+                    sn
+                ),
+                None => anyhow!("no device found for {:04x}:{:04x} in bootloader mode", vid, pid),
+            });
+        }
 
-            let path = info.path().to_string_lossy().to_string();
-            let device = api.open_path(info.path())?;
-            return Ok(TeensyDevice { device, path });
+        if matches.len() > 1 {
+            return Err(match serial {
+                Some(sn) => anyhow!(
+                    "{} devices found for {:04x}:{:04x} with serial {}",
+                    matches.len(),
+                    vid,
+                    pid,
+                    sn
+                ),
+                None => anyhow!(
+                    "{} devices found for {:04x}:{:04x}; use --serial to pick one",
+                    matches.len(),
+                    vid,
+                    pid
+                ),
+            });
         }
 
-        Err(anyhow!("no Teensy found in bootloader mode"))
+        let info = matches.remove(0);
+        let path = info.path().to_string_lossy().to_string();
+        let device = api.open_path(info.path())?;
+
+        Ok(TeensyDevice {
+            device,
+            path,
+            vid,
+            pid,
+            serial: serial.map(str::to_string),
+        })
     }
 
-    pub fn open_wait(timeout: Duration) -> Result<Self> {
+    pub fn open_wait(vid: u16, pid: u16, serial: Option<&str>, timeout: Duration) -> Result<Self> {
         let start = Instant::now();
         loop {
-            match Self::open() {
+            match Self::open(vid, pid, serial) {
                 Ok(dev) => return Ok(dev),
                 Err(_) if start.elapsed() < timeout => {
                     std::thread::sleep(Duration::from_millis(250));
@@ -45,12 +121,17 @@ impl TeensyDevice {
         }
     }
 
+    pub fn pid(&self) -> u16 {
+        self.pid
+    }
+
     pub fn write_report(&self, report: &[u8]) -> Result<usize, hidapi::HidError> {
         self.device.write(report)
     }
 
     // reopen the HID handle after a broken pipe (chip erase invalidates it)
-    // tries same path first, falls back to VID/PID scan
+    // tries same path first, falls back to a VID/PID/serial scan so a
+    // multi-board bench reattaches to the same physical device
     pub fn reopen(&mut self) -> bool {
         if let Ok(api) = HidApi::new() {
             if let Ok(cpath) = CString::new(self.path.clone()) {
@@ -61,9 +142,14 @@ impl TeensyDevice {
             }
 
             for info in api.device_list() {
-                if info.vendor_id() != TEENSY_VID || info.product_id() != TEENSY_4X_PID {
+                if info.vendor_id() != self.vid || info.product_id() != self.pid {
                     continue;
                 }
+                if let Some(want) = &self.serial {
+                    if info.serial_number() != Some(want.as_str()) {
+                        continue;
+                    }
+                }
                 if let Ok(dev) = api.open_path(info.path()) {
                     self.path = info.path().to_string_lossy().to_string();
                     self.device = dev;
@@ -76,12 +162,31 @@ impl TeensyDevice {
     }
 }
 
-pub fn list_devices() -> Result<Vec<String>> {
+/// open a running Teensy's application-mode "debug/seremu" interface, used
+/// to trigger a soft reboot into the HalfKay bootloader
+pub fn open_seremu(vid: u16, seremu_pid: u16) -> Result<HidDevice> {
+    let api = HidApi::new()?;
+
+    for info in api.device_list() {
+        if info.vendor_id() != vid || info.product_id() != seremu_pid {
+            continue;
+        }
+        return Ok(api.open_path(info.path())?);
+    }
+
+    Err(anyhow!(
+        "no Teensy found running application code ({:04x}:{:04x})",
+        vid,
+        seremu_pid
+    ))
+}
+
+pub fn list_devices(vid: u16, pid: u16) -> Result<Vec<String>> {
     let api = HidApi::new()?;
     let mut serials = Vec::new();
 
     for info in api.device_list() {
-        if info.vendor_id() != TEENSY_VID || info.product_id() != TEENSY_4X_PID {
+        if info.vendor_id() != vid || info.product_id() != pid {
             continue;
         }
         serials.push(info.serial_number().unwrap_or("unknown").to_string());